@@ -21,7 +21,9 @@ use crate::{
     KeyEvent, KeyState, Modifiers,
 };
 use keyboard_types::{Code, Key};
+use std::collections::{HashSet, VecDeque};
 use std::convert::TryFrom;
+use std::ffi::CString;
 use std::os::raw::c_char;
 use xkbcommon_sys::*;
 
@@ -127,6 +129,36 @@ impl Context {
         }
     }
 
+    /// Create a compose state for the user's locale.
+    ///
+    /// The locale is taken from `$XKB_DEFAULT_COMPOSE`, falling back to `$LANG` and then
+    /// to `"C"`, matching `libxkbcommon`'s own lookup. Returns `None` if the locale has no
+    /// Compose file (so the backend can simply skip compose handling). Backends opt in by
+    /// handing the result to [`State::set_compose_state`].
+    pub fn compose_state_from_locale(&self) -> Option<Compose> {
+        let locale = std::env::var("XKB_DEFAULT_COMPOSE")
+            .or_else(|_| std::env::var("LANG"))
+            .unwrap_or_else(|_| "C".to_string());
+        let locale = CString::new(locale).ok()?;
+        unsafe {
+            let table = xkb_compose_table_new_from_locale(
+                self.0,
+                locale.as_ptr(),
+                XKB_COMPOSE_COMPILE_NO_FLAGS,
+            );
+            if table.is_null() {
+                return None;
+            }
+            let state = xkb_compose_state_new(table, XKB_COMPOSE_STATE_NO_FLAGS);
+            // The state holds its own reference to the table.
+            xkb_compose_table_unref(table);
+            if state.is_null() {
+                return None;
+            }
+            Some(Compose(state))
+        }
+    }
+
     /// Set the log level using `tracing` levels.
     ///
     /// Because `xkb` has a `critical` error, each rust error maps to 1 above (e.g. error ->
@@ -184,9 +216,60 @@ impl Drop for Keymap {
     }
 }
 
+/// A compose/dead-key sequence tracker.
+///
+/// Wraps an `xkb_compose_state`, which turns a sequence of keysyms (such as the dead-acute
+/// `´` followed by `e`) into a single composed character (`é`).
+pub struct Compose(*mut xkb_compose_state);
+
+impl Compose {
+    /// Feed a keysym to the compose machine, returning the resulting status.
+    fn feed(&mut self, keysym: u32) -> xkb_compose_status {
+        unsafe {
+            xkb_compose_state_feed(self.0, keysym);
+            xkb_compose_state_get_status(self.0)
+        }
+    }
+
+    /// Reset the machine back to its initial state after a completed or cancelled sequence.
+    fn reset(&mut self) {
+        unsafe { xkb_compose_state_reset(self.0) };
+    }
+
+    /// The string produced by a completed sequence, if any.
+    fn utf8(&mut self) -> Option<String> {
+        unsafe {
+            // A first call with a zero-length buffer reports the length we need.
+            let len = xkb_compose_state_get_utf8(self.0, std::ptr::null_mut(), 0);
+            if len <= 0 {
+                return None;
+            }
+            let mut buf = vec![0u8; len as usize + 1];
+            xkb_compose_state_get_utf8(self.0, buf.as_mut_ptr() as *mut c_char, buf.len());
+            buf.truncate(len as usize);
+            String::from_utf8(buf).ok()
+        }
+    }
+}
+
+impl Clone for Compose {
+    fn clone(&self) -> Self {
+        Self(unsafe { xkb_compose_state_ref(self.0) })
+    }
+}
+
+impl Drop for Compose {
+    fn drop(&mut self) {
+        unsafe {
+            xkb_compose_state_unref(self.0);
+        }
+    }
+}
+
 pub struct State {
     state: *mut xkb_state,
     mods: ModsIndices,
+    compose: Option<Compose>,
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -225,9 +308,18 @@ impl State {
                 caps_lock: mod_idx(XKB_MOD_NAME_CAPS),
                 num_lock: mod_idx(XKB_MOD_NAME_NUM),
             },
+            compose: None,
         }
     }
 
+    /// Attach a compose state so that dead keys and Compose sequences are honoured.
+    ///
+    /// Build one with [`Context::compose_state_from_locale`]. Without it, `key_event`
+    /// reports every key as non-composing, as before.
+    pub fn set_compose_state(&mut self, compose: Compose) {
+        self.compose = Some(compose);
+    }
+
     pub fn update_xkb_state(&mut self, mods: ActiveModifiers) {
         unsafe {
             xkb_state_update_mask(
@@ -242,34 +334,38 @@ impl State {
         };
     }
 
+    /// Feed a raw keycode to the XKB action machinery, updating the modifier and layout
+    /// state in place.
+    ///
+    /// Unlike [`update_xkb_state`], which applies masks precomputed by an X server, this
+    /// runs `xkb_state_update_key` itself: it processes latches, locks and layout switches
+    /// from the keycode alone. Use it in an evdev/libinput pipeline where the compositor
+    /// hands us raw keycodes and expects us to maintain the state. Returns the mask of
+    /// state components that changed, so callers can tell whether the effective mods or
+    /// layout moved.
+    ///
+    /// [`update_xkb_state`]: State::update_xkb_state
+    pub fn update_key(&mut self, scancode: u32, direction: KeyState) -> xkb_state_component {
+        let direction = match direction {
+            KeyState::Down => XKB_KEY_DOWN,
+            KeyState::Up => XKB_KEY_UP,
+        };
+        unsafe { xkb_state_update_key(self.state, scancode, direction) }
+    }
+
     pub fn key_event(&mut self, scancode: u32, state: KeyState, repeat: bool) -> KeyEvent {
         let code = u16::try_from(scancode)
             .map(hardware_keycode_to_code)
             .unwrap_or(Code::Unidentified);
-        let key = self.get_logical_key(scancode);
+        let keysym = self.key_get_one_sym(scancode);
+        let (key, is_composing) = self.compose_key(keysym, state);
         // TODO this is lazy - really should use xkb i.e. augment the get_logical_key method.
         let location = code_to_location(code);
 
-        // TODO not sure how to get this
-        let is_composing = false;
-
-        let mut mods = Modifiers::empty();
-        // Update xkb's state (e.g. return capitals if we've pressed shift)
-        unsafe {
-            // compiler will unroll this loop
-            for (idx, mod_) in [
-                (self.mods.control, Modifiers::CONTROL),
-                (self.mods.shift, Modifiers::SHIFT),
-                (self.mods.super_, Modifiers::SUPER),
-                (self.mods.alt, Modifiers::ALT),
-                (self.mods.caps_lock, Modifiers::CAPS_LOCK),
-                (self.mods.num_lock, Modifiers::NUM_LOCK),
-            ] {
-                if xkb_state_mod_index_is_active(self.state, idx, XKB_STATE_MODS_EFFECTIVE) != 0 {
-                    mods |= mod_;
-                }
-            }
-        }
+        // The effective modifiers for the event (e.g. return capitals if we've pressed shift).
+        let mods = self.translate_mods(|idx| unsafe {
+            xkb_state_mod_index_is_active(self.state, idx, XKB_STATE_MODS_EFFECTIVE) != 0
+        });
         KeyEvent {
             state,
             key,
@@ -281,8 +377,69 @@ impl State {
         }
     }
 
-    fn get_logical_key(&mut self, scancode: u32) -> Key {
-        let keysym = self.key_get_one_sym(scancode);
+    /// Resolve `keysym` to a logical key, routing it through the compose machine when one
+    /// is attached.
+    ///
+    /// Returns the key and whether a compose sequence is still in progress. Only key-down
+    /// events advance the compose state; everything else takes the plain single-keysym
+    /// path. While composing we report [`Key::Dead`]; a completed sequence yields the
+    /// composed [`Key::Character`] and resets the machine.
+    fn compose_key(&mut self, keysym: u32, state: KeyState) -> (Key, bool) {
+        if state == KeyState::Down {
+            if let Some(compose) = self.compose.as_mut() {
+                match compose.feed(keysym) {
+                    XKB_COMPOSE_COMPOSING => return (Key::Dead(None), true),
+                    XKB_COMPOSE_COMPOSED => {
+                        let key = compose
+                            .utf8()
+                            .map(Key::Character)
+                            .unwrap_or(Key::Unidentified);
+                        compose.reset();
+                        return (key, false);
+                    }
+                    XKB_COMPOSE_CANCELLED => compose.reset(),
+                    _ => {}
+                }
+            }
+        }
+        (self.get_logical_key(keysym), false)
+    }
+
+    /// Map our modifier indices to a [`Modifiers`] set, including each one for which
+    /// `is_set` returns `true`.
+    fn translate_mods(&self, is_set: impl Fn(xkb_mod_index_t) -> bool) -> Modifiers {
+        let mut mods = Modifiers::empty();
+        // compiler will unroll this loop
+        for (idx, mod_) in [
+            (self.mods.control, Modifiers::CONTROL),
+            (self.mods.shift, Modifiers::SHIFT),
+            (self.mods.super_, Modifiers::SUPER),
+            (self.mods.alt, Modifiers::ALT),
+            (self.mods.caps_lock, Modifiers::CAPS_LOCK),
+            (self.mods.num_lock, Modifiers::NUM_LOCK),
+        ] {
+            if is_set(idx) {
+                mods |= mod_;
+            }
+        }
+        mods
+    }
+
+    /// The modifiers xkb "consumed" to produce the keysym for `scancode`.
+    ///
+    /// On some layouts a modifier is spent to reach the symbol itself — Shift to type `?`,
+    /// or level-shifting for an AltGr symbol. Such modifiers should not count when matching
+    /// accelerators, or shortcuts break on those layouts. Callers compare against
+    /// `event.mods & !state.consumed_mods(scancode)`. The GTK consumed-mode is used, which
+    /// is the least surprising for shortcut matching.
+    pub fn consumed_mods(&self, scancode: u32) -> Modifiers {
+        let mask = unsafe {
+            xkb_state_key_get_consumed_mods2(self.state, scancode, XKB_CONSUMED_MODE_GTK)
+        };
+        self.translate_mods(|idx| mask & (1 << idx) != 0)
+    }
+
+    fn get_logical_key(&mut self, keysym: u32) -> Key {
         let mut key = keycodes::map_key(keysym);
         if matches!(key, Key::Unidentified) {
             if let Some(s) = self.key_get_utf8(keysym) {
@@ -320,6 +477,7 @@ impl Clone for State {
         Self {
             state: unsafe { xkb_state_ref(self.state) },
             mods: self.mods,
+            compose: self.compose.clone(),
         }
     }
 }
@@ -331,3 +489,68 @@ impl Drop for State {
         }
     }
 }
+
+/// Number of filtered modifier keycodes we buffer before dropping the oldest.
+const MODIFIER_REPLAY_CAPACITY: usize = 32;
+
+/// Reconciles xkb modifier state after the input method swallows modifier keys.
+///
+/// On X11 a modifier keycode can be filtered out by XIM while a preedit is active, which
+/// leaves the [`State`]'s modifier bits stale and breaks shortcuts issued right afterwards.
+/// Each filtered modifier keycode is buffered here; when an event finally passes through
+/// unfiltered, [`flush`] replays the buffered keycodes through [`State::update_key`] so the
+/// modifier state is back in sync before the real event is processed. The buffer is bounded,
+/// dropping the oldest keycode once full.
+///
+/// [`flush`]: ModifierReplay::flush
+pub struct ModifierReplay {
+    // The keycodes the backend recognises as modifiers; only these are ever buffered.
+    modifiers: HashSet<u32>,
+    // Filtered modifier transitions, oldest first, capped at `MODIFIER_REPLAY_CAPACITY`. Each
+    // entry records the keycode and the direction it was filtered in, so a press that was
+    // swallowed while the key stays held replays as a press (and leaves xkb holding it).
+    filtered: VecDeque<(u32, KeyState)>,
+}
+
+impl ModifierReplay {
+    /// Create a replay buffer for the given set of modifier keycodes.
+    ///
+    /// The backend builds the lookup once from its keymap — the keycodes of Shift, Control,
+    /// Alt and friends.
+    pub fn new(modifiers: HashSet<u32>) -> Self {
+        Self {
+            modifiers,
+            filtered: VecDeque::with_capacity(MODIFIER_REPLAY_CAPACITY),
+        }
+    }
+
+    /// Whether `keycode` is a modifier this buffer tracks.
+    pub fn is_modifier(&self, keycode: u32) -> bool {
+        self.modifiers.contains(&keycode)
+    }
+
+    /// Record a modifier transition that the IME filtered.
+    ///
+    /// Non-modifier keycodes are ignored. When the buffer is full the oldest entry is dropped.
+    pub fn push_filtered(&mut self, keycode: u32, direction: KeyState) {
+        if !self.is_modifier(keycode) {
+            return;
+        }
+        if self.filtered.len() == MODIFIER_REPLAY_CAPACITY {
+            self.filtered.pop_front();
+        }
+        self.filtered.push_back((keycode, direction));
+    }
+
+    /// Replay and clear the buffered modifier transitions through `state`.
+    ///
+    /// Call this for an event that was *not* filtered, before processing it, so the xkb
+    /// modifier state reflects the transitions the IME swallowed. Each keycode is fed in the
+    /// direction it was filtered, so a modifier whose press was swallowed while it is still
+    /// held stays down after the flush.
+    pub fn flush(&mut self, state: &mut State) {
+        for (keycode, direction) in self.filtered.drain(..) {
+            state.update_key(keycode, direction);
+        }
+    }
+}
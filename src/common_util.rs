@@ -24,13 +24,18 @@ use std::time::Duration;
 use instant::Instant;
 
 use crate::kurbo::Point;
-use crate::WinHandler;
+use crate::{KeyEvent, WinHandler};
 
 // This is the default timing on windows.
 const MULTI_CLICK_INTERVAL: Duration = Duration::from_millis(500);
 // the max distance between two clicks for them to count as a multi-click
 const MULTI_CLICK_MAX_DISTANCE: f64 = 5.0;
 
+// How long a key must be held before it starts repeating.
+const KEY_REPEAT_DELAY: Duration = Duration::from_millis(500);
+// Interval between repeats once repeating has begun (~40 repeats per second).
+const KEY_REPEAT_INTERVAL: Duration = Duration::from_millis(25);
+
 /// Strip the access keys from the menu string.
 ///
 /// Changes "E&xit" to "Exit". Actual ampersands are escaped as "&&".
@@ -244,3 +249,118 @@ impl Default for ClickCounter {
         ClickCounter::new(MULTI_CLICK_INTERVAL, MULTI_CLICK_MAX_DISTANCE)
     }
 }
+
+/// The currently repeating key tracked by a [`KeyRepeater`].
+#[derive(Debug, Clone)]
+struct Repeat {
+    // The raw scancode of the held key, used to match up the key-up that cancels it.
+    scancode: u32,
+    // The event to re-emit on each repeat. Its `repeat` field is already `true`.
+    event: KeyEvent,
+    // The instant at which the next synthetic event is due.
+    next_fire: Instant,
+}
+
+/// Generates repeated [`KeyEvent`]s while a key is held down.
+///
+/// A single key may be repeating at a time. [`key_down`] arms the repeater when the
+/// pressed key repeats according to the keymap; the first synthetic event is due one
+/// `delay` later and subsequent ones every `rate` after that. Pressing any other key
+/// cancels the held key's repeat (re-arming for the new key if that key repeats), and
+/// [`key_up`] cancels if it matches the held key. The event
+/// loop drains the due events with [`pump`], which never blocks.
+///
+/// This mirrors the timer-driven approach used by smithay-client-toolkit: rather than
+/// spawning a thread, we record the next-fire [`Instant`] and let the loop poll it.
+///
+/// [`key_down`]: KeyRepeater::key_down
+/// [`key_up`]: KeyRepeater::key_up
+/// [`pump`]: KeyRepeater::pump
+#[derive(Debug, Clone)]
+pub struct KeyRepeater {
+    delay: Duration,
+    rate: Duration,
+    repeat: Option<Repeat>,
+}
+
+#[allow(dead_code)]
+impl KeyRepeater {
+    /// Create a new repeater with the given initial delay and repeat rate.
+    pub fn new(delay: Duration, rate: Duration) -> KeyRepeater {
+        KeyRepeater {
+            delay,
+            rate,
+            repeat: None,
+        }
+    }
+
+    /// Set the delay between a key-down and the first repeat.
+    pub fn set_delay(&mut self, delay: Duration) {
+        self.delay = delay;
+    }
+
+    /// Set the interval between repeats once repeating has begun.
+    pub fn set_rate(&mut self, rate: Duration) {
+        self.rate = rate;
+    }
+
+    /// Begin repeating `event` for the key `scancode`.
+    ///
+    /// Any key currently repeating is cancelled, so this may be called for every key-down:
+    /// pressing a non-repeating key simply stops the previous one (pass a `repeats` of
+    /// `false` for that). The first synthetic event becomes due `delay` after this call.
+    pub fn key_down(&mut self, scancode: u32, event: KeyEvent, repeats: bool) {
+        if !repeats {
+            self.repeat = None;
+            return;
+        }
+        let mut event = event;
+        event.repeat = true;
+        self.repeat = Some(Repeat {
+            scancode,
+            event,
+            next_fire: Instant::now() + self.delay,
+        });
+    }
+
+    /// Stop repeating if `scancode` is the key currently being repeated.
+    pub fn key_up(&mut self, scancode: u32) {
+        if matches!(&self.repeat, Some(r) if r.scancode == scancode) {
+            self.repeat = None;
+        }
+    }
+
+    /// Cancel any in-progress repeat.
+    pub fn cancel(&mut self) {
+        self.repeat = None;
+    }
+
+    /// The instant at which the next synthetic event is due, if a key is repeating.
+    ///
+    /// The event loop can use this to decide how long to wait before the next [`pump`].
+    ///
+    /// [`pump`]: KeyRepeater::pump
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.repeat.as_ref().map(|r| r.next_fire)
+    }
+
+    /// Return the next synthetic event if one is due at `now`, rescheduling the next repeat.
+    ///
+    /// At most one event is produced per call: missed deadlines are coalesced by
+    /// rescheduling from `now` rather than the old deadline, so an event-loop stall can
+    /// never turn into a burst of backlogged repeats.
+    pub fn pump(&mut self, now: Instant) -> Option<KeyEvent> {
+        let repeat = self.repeat.as_mut()?;
+        if now < repeat.next_fire {
+            return None;
+        }
+        repeat.next_fire = now + self.rate;
+        Some(repeat.event.clone())
+    }
+}
+
+impl Default for KeyRepeater {
+    fn default() -> Self {
+        KeyRepeater::new(KEY_REPEAT_DELAY, KEY_REPEAT_INTERVAL)
+    }
+}